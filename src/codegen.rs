@@ -0,0 +1,229 @@
+use std::path::Path;
+
+use anyhow::Context;
+use naga::{AddressSpace, ImageClass, Module, StorageAccess, TypeInner};
+
+use crate::manifest::ShaderKind;
+
+/// Reflected metadata for a single compiled shader permutation, kept alongside the
+/// generated identifier and output path until [`render`] turns it into source text.
+pub struct GeneratedShader {
+    const_name: String,
+    bytes_path: String,
+    reflection: ShaderReflection,
+}
+
+impl GeneratedShader {
+    /// `output_path`'s location relative to `target_dir` (directory, file name and
+    /// any permutation suffix) is used to derive the generated identifier, so two
+    /// shaders with the same manifest name in different subdirectories don't
+    /// collide on the same `pub static` in `shaders.rs`.
+    pub fn new(output_path: &Path, target_dir: &Path, reflection: ShaderReflection) -> Self {
+        let relative_path = pathdiff::diff_paths(output_path, target_dir)
+            .unwrap_or_else(|| output_path.to_owned());
+        let ident = relative_path
+            .with_extension("")
+            .to_string_lossy()
+            .into_owned();
+
+        Self {
+            const_name: screaming_snake_case(&ident),
+            bytes_path: relative_path.to_string_lossy().replace('\\', "/"),
+            reflection,
+        }
+    }
+}
+
+fn screaming_snake_case(ident: &str) -> String {
+    ident
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    UniformBuffer,
+    StorageBuffer { read: bool, write: bool },
+    SampledTexture,
+    StorageTexture { read: bool, write: bool },
+    Sampler,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BindingInfo {
+    pub group: u32,
+    pub binding: u32,
+    pub kind: BindingKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkgroupBufferInfo {
+    pub name: String,
+    pub byte_size: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShaderReflection {
+    pub bindings: Vec<BindingInfo>,
+    pub workgroup_buffers: Vec<WorkgroupBufferInfo>,
+    pub workgroup_size: Option<[u32; 3]>,
+}
+
+/// Walks a parsed module's global variables and entry points to recover the bind
+/// group layout and workgroup memory footprint that compiling to bytes discards.
+pub fn reflect(
+    module: &Module,
+    kind: ShaderKind,
+    entry_point: &str,
+) -> anyhow::Result<ShaderReflection> {
+    let mut layouter = naga::proc::Layouter::default();
+    layouter
+        .update(&module.types, &module.constants)
+        .context("failed to lay out shader types for reflection")?;
+
+    let mut bindings = Vec::new();
+    let mut workgroup_buffers = Vec::new();
+
+    for (_, variable) in module.global_variables.iter() {
+        if let Some(binding) = &variable.binding {
+            let kind = match (&variable.space, &module.types[variable.ty].inner) {
+                (AddressSpace::Uniform, _) => Some(BindingKind::UniformBuffer),
+                (AddressSpace::Storage { access }, _) => Some(BindingKind::StorageBuffer {
+                    read: access.contains(StorageAccess::LOAD),
+                    write: access.contains(StorageAccess::STORE),
+                }),
+                (AddressSpace::Handle, TypeInner::Image { class, .. }) => match class {
+                    ImageClass::Sampled { .. } | ImageClass::Depth { .. } => {
+                        Some(BindingKind::SampledTexture)
+                    }
+                    ImageClass::Storage { access, .. } => Some(BindingKind::StorageTexture {
+                        read: access.contains(StorageAccess::LOAD),
+                        write: access.contains(StorageAccess::STORE),
+                    }),
+                },
+                (AddressSpace::Handle, TypeInner::Sampler { .. }) => Some(BindingKind::Sampler),
+                _ => None,
+            };
+
+            if let Some(kind) = kind {
+                bindings.push(BindingInfo {
+                    group: binding.group,
+                    binding: binding.binding,
+                    kind,
+                });
+            }
+        } else if variable.space == AddressSpace::WorkGroup {
+            workgroup_buffers.push(WorkgroupBufferInfo {
+                name: variable.name.clone().unwrap_or_default(),
+                byte_size: layouter[variable.ty].size,
+            });
+        }
+    }
+
+    let stage = naga::ShaderStage::from(kind);
+    let workgroup_size = module
+        .entry_points
+        .iter()
+        .find(|ep| ep.stage == stage && ep.name == entry_point)
+        .map(|ep| ep.workgroup_size);
+
+    Ok(ShaderReflection {
+        bindings,
+        workgroup_buffers,
+        workgroup_size,
+    })
+}
+
+/// Renders the collected shader reflections into a standalone `shaders.rs` that
+/// downstream renderer crates can `include!` to build bind group layouts without
+/// hand-writing them.
+pub fn render(shaders: &[GeneratedShader]) -> String {
+    let mut out = String::new();
+    out.push_str("// This file is generated by shadermake. Do not edit by hand.\n\n");
+    out.push_str(
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\n\
+         pub enum BindingKind {\n\
+        \x20   UniformBuffer,\n\
+        \x20   StorageBuffer { read: bool, write: bool },\n\
+        \x20   SampledTexture,\n\
+        \x20   StorageTexture { read: bool, write: bool },\n\
+        \x20   Sampler,\n\
+         }\n\n\
+         #[derive(Debug, Clone, Copy)]\n\
+         pub struct BindingInfo {\n\
+        \x20   pub group: u32,\n\
+        \x20   pub binding: u32,\n\
+        \x20   pub kind: BindingKind,\n\
+         }\n\n\
+         #[derive(Debug, Clone, Copy)]\n\
+         pub struct WorkgroupBufferInfo {\n\
+        \x20   pub name: &'static str,\n\
+        \x20   pub byte_size: u32,\n\
+         }\n\n\
+         #[derive(Debug, Clone, Copy)]\n\
+         pub struct Shader {\n\
+        \x20   pub bytes: &'static [u8],\n\
+        \x20   pub bindings: &'static [BindingInfo],\n\
+        \x20   pub workgroup_buffers: &'static [WorkgroupBufferInfo],\n\
+        \x20   pub workgroup_size: Option<[u32; 3]>,\n\
+         }\n\n",
+    );
+
+    for shader in shaders {
+        out.push_str(&format!(
+            "pub static {}: Shader = Shader {{\n    bytes: include_bytes!(\"{}\"),\n",
+            shader.const_name, shader.bytes_path
+        ));
+
+        out.push_str("    bindings: &[\n");
+        for binding in &shader.reflection.bindings {
+            out.push_str(&format!(
+                "        BindingInfo {{ group: {}, binding: {}, kind: BindingKind::{} }},\n",
+                binding.group,
+                binding.binding,
+                render_binding_kind(binding.kind)
+            ));
+        }
+        out.push_str("    ],\n");
+
+        out.push_str("    workgroup_buffers: &[\n");
+        for buffer in &shader.reflection.workgroup_buffers {
+            out.push_str(&format!(
+                "        WorkgroupBufferInfo {{ name: \"{}\", byte_size: {} }},\n",
+                buffer.name, buffer.byte_size
+            ));
+        }
+        out.push_str("    ],\n");
+
+        out.push_str(&format!(
+            "    workgroup_size: {},\n}};\n\n",
+            match shader.reflection.workgroup_size {
+                Some([x, y, z]) => format!("Some([{}, {}, {}])", x, y, z),
+                None => "None".to_owned(),
+            }
+        ));
+    }
+
+    out
+}
+
+fn render_binding_kind(kind: BindingKind) -> String {
+    match kind {
+        BindingKind::UniformBuffer => "UniformBuffer".to_owned(),
+        BindingKind::StorageBuffer { read, write } => {
+            format!("StorageBuffer {{ read: {}, write: {} }}", read, write)
+        }
+        BindingKind::SampledTexture => "SampledTexture".to_owned(),
+        BindingKind::StorageTexture { read, write } => {
+            format!("StorageTexture {{ read: {}, write: {} }}", read, write)
+        }
+        BindingKind::Sampler => "Sampler".to_owned(),
+    }
+}
@@ -1,28 +1,39 @@
 #![feature(or_patterns)]
 
 use std::{
+    collections::HashSet,
     fmt::Display,
     fs,
     path::{Path, PathBuf},
     str::FromStr,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::channel,
+        Mutex,
+    },
+    time::Duration,
 };
 
 use anyhow::{anyhow, Context};
-use manifest::{Manifest, ShaderKind};
+use manifest::{Manifest, Permutation, ShaderKind};
 use naga::{
     back::spv::{Capability, WriterFlags},
     FastHashSet,
 };
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
 use rayon::prelude::*;
 
+mod codegen;
 mod manifest;
+mod preprocess;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Target {
     Spirv,
     Wgsl,
     Glsl,
+    Msl,
+    Hlsl,
 }
 
 impl Target {
@@ -31,6 +42,8 @@ impl Target {
             Target::Spirv => "spv",
             Target::Wgsl => "wgsl",
             Target::Glsl => "glsl",
+            Target::Msl => "metal",
+            Target::Hlsl => "hlsl",
         }
     }
 }
@@ -43,8 +56,10 @@ impl FromStr for Target {
             "spv" | "spirv" => Ok(Target::Spirv),
             "wgsl" => Ok(Target::Wgsl),
             "glsl" => Ok(Target::Glsl),
+            "msl" | "metal" => Ok(Target::Msl),
+            "hlsl" => Ok(Target::Hlsl),
             s => Err(anyhow!(
-                "invalid target '{}' (expected: spv, spirv, wgsl, glsl)",
+                "invalid target '{}' (expected: spv, spirv, wgsl, glsl, msl, hlsl)",
                 s
             )),
         }
@@ -56,6 +71,10 @@ pub struct Options {
     pub source_dir: PathBuf,
     pub target_dir: PathBuf,
     pub target: Target,
+    /// When set, also emit a `shaders.rs` in `target_dir` with reflected bind group
+    /// and workgroup metadata for each compiled WGSL shader. GLSL sources are not
+    /// reflected and do not appear in it.
+    pub generate_bindings: bool,
 }
 
 pub trait Logger: Send + Sync {
@@ -68,22 +87,104 @@ pub trait Logger: Send + Sync {
     fn on_completed(&self);
 }
 
-pub fn build(options: &Options, logger: &dyn Logger) -> anyhow::Result<()> {
+/// Compiles every shader found under `options.source_dir`, reporting progress and
+/// errors through `logger`. Returns `Ok(true)` if every shader compiled
+/// successfully, `Ok(false)` if at least one failed; the caller decides what to do
+/// with a partial failure (e.g. exit with a non-zero code, or keep watching).
+pub fn build(options: &Options, logger: &dyn Logger) -> anyhow::Result<bool> {
     let shaders = gather_shaders(&options.source_dir)?;
     logger.on_shaders_gathered(shaders.0.len());
 
     let success = AtomicBool::new(true);
+    let generated = Mutex::new(Vec::new());
 
     shaders.0.into_par_iter().for_each(|shader| {
         logger.on_compiling(&shader.name);
-        if let Err(e) = compile(&shader, options) {
-            logger.on_compile_error(&shader.name, &format!("{:?}", e));
-            success.store(false, Ordering::SeqCst);
+        match compile(&shader, options) {
+            Ok(shaders_generated) => generated.lock().unwrap().extend(shaders_generated),
+            Err(e) => {
+                logger.on_compile_error(&shader.name, &format!("{:?}", e));
+                success.store(false, Ordering::SeqCst);
+            }
         }
     });
 
-    let exit_code = if success.load(Ordering::SeqCst) { 0 } else { 1 };
-    std::process::exit(exit_code);
+    if options.generate_bindings {
+        let rust_module = codegen::render(&generated.into_inner().unwrap());
+        let rust_module_path = options.target_dir.join("shaders.rs");
+        if let Some(parent) = rust_module_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        fs::write(&rust_module_path, rust_module)?;
+    }
+
+    logger.on_completed();
+    Ok(success.load(Ordering::SeqCst))
+}
+
+/// Runs an initial full `build`, then watches `options.source_dir` and recompiles
+/// whichever shaders are affected by each change instead of restarting the process.
+/// A compile failure is reported through `logger` and never aborts the watch loop.
+pub fn build_watch(options: &Options, logger: &dyn Logger) -> anyhow::Result<()> {
+    build(options, logger)?;
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(200))
+        .context("failed to create filesystem watcher")?;
+    watcher
+        .watch(&options.source_dir, RecursiveMode::Recursive)
+        .with_context(|| format!("failed to watch {}", options.source_dir.display()))?;
+
+    loop {
+        let event = match rx.recv() {
+            Ok(event) => event,
+            Err(_) => break,
+        };
+
+        let changed_path = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Rename(_, path) => path,
+            _ => continue,
+        };
+
+        if changed_path.file_name().and_then(|name| name.to_str()) == Some("shadermake.toml") {
+            if let Err(e) = build(options, logger) {
+                logger.on_compile_error("shadermake.toml", &format!("{:?}", e));
+            }
+            continue;
+        }
+
+        if options.generate_bindings {
+            // An incremental recompile only touches the changed shader, but
+            // `shaders.rs` bundles every shader's reflection into one file, so a
+            // partial rebuild would leave it stale. Do a full rebuild instead.
+            if let Err(e) = build(options, logger) {
+                logger.on_compile_error(&changed_path.display().to_string(), &format!("{:?}", e));
+            }
+            continue;
+        }
+
+        let shaders = match gather_shaders(&options.source_dir) {
+            Ok(shaders) => shaders,
+            Err(e) => {
+                logger.on_compile_error(&changed_path.display().to_string(), &format!("{:?}", e));
+                continue;
+            }
+        };
+
+        for shader in shaders.0 {
+            if options.source_dir.join(&shader.path) != changed_path {
+                continue;
+            }
+            logger.on_compiling(&shader.name);
+            if let Err(e) = compile(&shader, options) {
+                logger.on_compile_error(&shader.name, &format!("{:?}", e));
+            }
+        }
+    }
+
+    Ok(())
 }
 
 struct ShadersToCompile(Vec<ShaderToCompile>);
@@ -92,6 +193,8 @@ struct ShaderToCompile {
     name: String,
     path: PathBuf,
     kind: ShaderKind,
+    permutations: Vec<Permutation>,
+    entry_point: String,
 }
 
 fn gather_shaders(source_dir: &Path) -> anyhow::Result<ShadersToCompile> {
@@ -112,6 +215,8 @@ fn gather_shaders(source_dir: &Path) -> anyhow::Result<ShadersToCompile> {
                 name: shader_name,
                 path: directory.join(&shader.path),
                 kind: shader.kind,
+                permutations: shader.permutations,
+                entry_point: shader.entry_point,
             };
             shaders.push(shader);
         }
@@ -124,23 +229,105 @@ fn gather_shaders(source_dir: &Path) -> anyhow::Result<ShadersToCompile> {
     Ok(ShadersToCompile(shaders))
 }
 
-fn compile(shader: &ShaderToCompile, options: &Options) -> anyhow::Result<()> {
+fn compile(
+    shader: &ShaderToCompile,
+    options: &Options,
+) -> anyhow::Result<Vec<codegen::GeneratedShader>> {
     let source_path = options.source_dir.join(&shader.path);
-    let source = fs::read(&source_path)
+    let source = fs::read_to_string(&source_path)
         .with_context(|| format!("failed to read {}", source_path.display()))?;
-
-    let output = compile_source(&source, shader, options)?;
-
     let base_path =
         pathdiff::diff_paths(&source_path, &options.source_dir).context("no base path")?;
-    let mut target_path = options.target_dir.join(&base_path);
+
+    let mut generated = Vec::new();
+
+    if shader.permutations.is_empty() {
+        let (output, reflection) = compile_permutation(&source, shader, options, &HashSet::new())?;
+        let target_path = write_output(&output, &base_path, None, options)?;
+        if let Some(reflection) = reflection {
+            generated.push(codegen::GeneratedShader::new(
+                &target_path,
+                &options.target_dir,
+                reflection,
+            ));
+        }
+    } else {
+        for permutation in &shader.permutations {
+            let defines: HashSet<String> = permutation.defines.iter().cloned().collect();
+            let (output, reflection) = compile_permutation(&source, shader, options, &defines)
+                .with_context(|| format!("failed to compile permutation '{}'", permutation.name))?;
+            let target_path = write_output(&output, &base_path, Some(&permutation.name), options)?;
+            if let Some(reflection) = reflection {
+                generated.push(codegen::GeneratedShader::new(
+                    &target_path,
+                    &options.target_dir,
+                    reflection,
+                ));
+            }
+        }
+    }
+
+    Ok(generated)
+}
+
+fn compile_permutation(
+    source: &str,
+    shader: &ShaderToCompile,
+    options: &Options,
+    defines: &HashSet<String>,
+) -> anyhow::Result<(Vec<u8>, Option<codegen::ShaderReflection>)> {
+    let preprocessed =
+        preprocess::preprocess(source, defines).context("failed to preprocess shader")?;
+
+    // WGSL is always parsed and validated here, regardless of target or whether
+    // `--generate-bindings` is set, so an invalid shader is caught rather than
+    // written straight through by an identity/passthrough target like `wgsl`. The
+    // parsed module is then reused directly for both reflection and codegen
+    // instead of being re-derived from source by `compile_source`.
+    if let Some(ShaderSourceKind::Wgsl) = ShaderSourceKind::guess(&shader.path) {
+        let module = parse_and_validate_wgsl(&preprocessed)?;
+        let reflection = if options.generate_bindings {
+            Some(codegen::reflect(&module, shader.kind, &shader.entry_point)?)
+        } else {
+            None
+        };
+        let output = compile_naga(&module, shader.kind, options.target, &shader.entry_point)
+            .context("failed to compile shader")?;
+        return Ok((output, reflection));
+    }
+
+    let output = compile_source(preprocessed.as_bytes(), shader, options)?;
+    Ok((output, None))
+}
+
+fn write_output(
+    output: &[u8],
+    base_path: &Path,
+    permutation_name: Option<&str>,
+    options: &Options,
+) -> anyhow::Result<PathBuf> {
+    let mut target_path = options.target_dir.join(base_path);
+    if let Some(name) = permutation_name {
+        let stem = target_path
+            .file_stem()
+            .context("shader output path has no file name")?
+            .to_owned();
+        let mut file_name = stem;
+        file_name.push(".");
+        file_name.push(name);
+        // Add the extension here: `set_extension` below would otherwise replace the
+        // permutation name, since it is the last dot-separated part of the file name.
+        file_name.push(".");
+        file_name.push(options.target.extension());
+        target_path.set_file_name(file_name);
+    }
     target_path.set_extension(options.target.extension());
     if let Some(parent) = target_path.parent() {
         let _ = std::fs::create_dir_all(parent);
     }
-    fs::write(&target_path, &output)?;
+    fs::write(&target_path, output)?;
 
-    Ok(())
+    Ok(target_path)
 }
 
 fn compile_source(
@@ -154,8 +341,8 @@ fn compile_source(
     let compile_fn = compile_fn(source_kind, options.target)
         .context("failed to find a suitable compilation tool for shader")?;
 
-    let result =
-        compile_fn(source, shader.kind, options.target).context("failed to compile shader")?;
+    let result = compile_fn(source, shader.kind, options.target, &shader.entry_point)
+        .context("failed to compile shader")?;
     Ok(result)
 }
 
@@ -174,31 +361,64 @@ impl ShaderSourceKind {
     }
 }
 
+/// Dispatches everything except WGSL sources, which `compile_permutation`
+/// already parses, validates and compiles directly via `compile_naga` so the
+/// module only gets parsed once.
 fn compile_fn(
     source_kind: ShaderSourceKind,
     target: Target,
-) -> Option<fn(&[u8], ShaderKind, Target) -> anyhow::Result<Vec<u8>>> {
+) -> Option<fn(&[u8], ShaderKind, Target, &str) -> anyhow::Result<Vec<u8>>> {
     match (source_kind, target) {
-        (ShaderSourceKind::Wgsl, Target::Glsl | Target::Spirv) => Some(compile_naga_wgsl),
-        (ShaderSourceKind::Wgsl, Target::Wgsl) => Some(compile_identity),
+        (ShaderSourceKind::Wgsl, _) => None,
         (ShaderSourceKind::Glsl, Target::Spirv) => Some(compile_shaderc_glsl),
-        (ShaderSourceKind::Glsl, Target::Wgsl) => None,
+        (ShaderSourceKind::Glsl, Target::Wgsl | Target::Msl | Target::Hlsl) => {
+            Some(compile_naga_glsl)
+        }
         (ShaderSourceKind::Glsl, Target::Glsl) => Some(compile_identity),
     }
 }
 
-fn compile_naga_wgsl(source: &[u8], kind: ShaderKind, target: Target) -> anyhow::Result<Vec<u8>> {
-    let module = naga::front::wgsl::parse_str(std::str::from_utf8(source)?)
-        .ok()
-        .context("failed to parse WGSL")?;
+fn compile_naga_glsl(
+    source: &[u8],
+    kind: ShaderKind,
+    target: Target,
+    entry_point: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let source = std::str::from_utf8(source)?;
+    let options = naga::front::glsl::Options {
+        stage: naga::ShaderStage::from(kind),
+        defines: naga::FastHashMap::default(),
+    };
+    let module = naga::front::glsl::Parser::default()
+        .parse(&options, source)
+        .map_err(|errors| anyhow!("failed to parse GLSL shader: {:?}", errors))?;
+
+    compile_naga(&module, kind, target, entry_point)
+}
 
-    compile_naga(&module, kind, target)
+/// Parses WGSL and runs it through naga's validator, rendering any parse or
+/// validation failure as a codespan-style diagnostic that points at the offending
+/// source span instead of a generic "failed to parse" message.
+fn parse_and_validate_wgsl(source: &str) -> anyhow::Result<naga::Module> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| anyhow!("failed to parse WGSL shader:\n{}", e.emit_to_string(source)))?;
+
+    let mut validator = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    );
+    validator
+        .validate(&module)
+        .map_err(|e| anyhow!("WGSL shader failed validation:\n{}", e.emit_to_string(source)))?;
+
+    Ok(module)
 }
 
 fn compile_naga(
     module: &naga::Module,
     kind: ShaderKind,
     target: Target,
+    entry_point: &str,
 ) -> anyhow::Result<Vec<u8>> {
     let stage = naga::ShaderStage::from(kind);
 
@@ -213,24 +433,60 @@ fn compile_naga(
             let mut vec = Vec::new();
             let options = naga::back::glsl::Options {
                 version: naga::back::glsl::Version::Desktop(450),
-                entry_point: (stage, "main".to_owned()),
+                entry_point: (stage, entry_point.to_owned()),
             };
             let mut writer = naga::back::glsl::Writer::new(&mut vec, &module, &options)?;
             writer.write()?;
 
             Ok(vec)
         }
-        Target::Wgsl => unreachable!(),
+        Target::Msl => {
+            let mut per_entry_point_map = naga::back::msl::EntryPointResourceMap::default();
+            per_entry_point_map.insert(
+                entry_point.to_owned(),
+                naga::back::msl::EntryPointResources::default(),
+            );
+            let options = naga::back::msl::Options {
+                lang_version: (2, 0),
+                per_entry_point_map,
+                inline_samplers: Vec::new(),
+                spirv_cross_compatibility: false,
+                fake_missing_bindings: true,
+            };
+            let pipeline_options = naga::back::msl::PipelineOptions {
+                allow_point_size: false,
+            };
+            let (text, _info) = naga::back::msl::write_string(&module, &options, &pipeline_options)?;
+            Ok(text.into_bytes())
+        }
+        Target::Hlsl => {
+            let options = naga::back::hlsl::Options {
+                shader_model: naga::back::hlsl::ShaderModel::V5_1,
+                binding_map: Default::default(),
+                fake_missing_bindings: true,
+            };
+            let text = naga::back::hlsl::write_string(&module, &options)?;
+            Ok(text.into_bytes())
+        }
+        Target::Wgsl => {
+            let text = naga::back::wgsl::write_string(&module, naga::back::wgsl::WriterFlags::empty())?;
+            Ok(text.into_bytes())
+        }
     }
 }
 
-fn compile_identity(source: &[u8], _: ShaderKind, _: Target) -> anyhow::Result<Vec<u8>> {
+fn compile_identity(source: &[u8], _: ShaderKind, _: Target, _: &str) -> anyhow::Result<Vec<u8>> {
     Ok(source.to_vec())
 }
 
-fn compile_shaderc_glsl(source: &[u8], kind: ShaderKind, _: Target) -> anyhow::Result<Vec<u8>> {
+fn compile_shaderc_glsl(
+    source: &[u8],
+    kind: ShaderKind,
+    _: Target,
+    entry_point: &str,
+) -> anyhow::Result<Vec<u8>> {
     let mut compiler = shaderc::Compiler::new().context("failed to create shaderc compiler")?;
     let source = std::str::from_utf8(source)?;
-    let spirv = compiler.compile_into_spirv(source, kind.into(), "", "main", None)?;
+    let spirv = compiler.compile_into_spirv(source, kind.into(), "", entry_point, None)?;
     Ok(spirv.as_binary_u8().to_vec())
 }
@@ -15,6 +15,12 @@ struct CliOptions {
     #[argh(default = "Target::Spirv")]
     /// the target shader kind to compile to
     target: Target,
+    #[argh(switch)]
+    /// also emit a `shaders.rs` with reflected bind group and workgroup metadata
+    generate_bindings: bool,
+    #[argh(switch)]
+    /// keep running and recompile shaders as their source files change
+    watch: bool,
 }
 
 struct Logger;
@@ -47,6 +53,13 @@ fn main() -> anyhow::Result<()> {
         source_dir: std::env::current_dir()?,
         target_dir: cli_args.target_dir,
         target: cli_args.target,
+        generate_bindings: cli_args.generate_bindings,
     };
-    shadermake::build(&options, &Logger)
+
+    if cli_args.watch {
+        shadermake::build_watch(&options, &Logger)
+    } else {
+        let success = shadermake::build(&options, &Logger)?;
+        std::process::exit(if success { 0 } else { 1 });
+    }
 }
@@ -21,6 +21,23 @@ impl Manifest {
 pub struct Shader {
     pub path: String,
     pub kind: ShaderKind,
+    #[serde(default)]
+    pub permutations: Vec<Permutation>,
+    #[serde(default = "default_entry_point")]
+    pub entry_point: String,
+}
+
+fn default_entry_point() -> String {
+    "main".to_owned()
+}
+
+/// A named preprocessor variant of a shader, compiled as its own output file with
+/// `permutation.name` appended to the base filename.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Permutation {
+    pub name: String,
+    #[serde(default)]
+    pub defines: Vec<String>,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize)]
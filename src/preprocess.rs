@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+
+/// Expands `#ifdef` / `#ifndef` / `#else` / `#endif` directives against a set of active
+/// defines, stripping inactive regions before the source reaches a compiler backend.
+pub fn preprocess(source: &str, defines: &HashSet<String>) -> anyhow::Result<String> {
+    let mut output = String::with_capacity(source.len());
+    // Whether the branch opened at each nesting level is active, independent of its parent;
+    // a line is emitted only when every entry in the stack is true.
+    let mut stack: Vec<bool> = Vec::new();
+    let mut else_seen: Vec<bool> = Vec::new();
+
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("#ifdef") {
+            stack.push(defines.contains(name.trim()));
+            else_seen.push(false);
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix("#ifndef") {
+            stack.push(!defines.contains(name.trim()));
+            else_seen.push(false);
+            continue;
+        }
+        if trimmed == "#else" {
+            let active = stack
+                .last_mut()
+                .ok_or_else(|| anyhow!("`#else` without matching `#ifdef`/`#ifndef`"))?;
+            let seen = else_seen.last_mut().expect("stack and else_seen stay in sync");
+            if *seen {
+                return Err(anyhow!("duplicate `#else` for the same `#ifdef`/`#ifndef`"));
+            }
+            *seen = true;
+            *active = !*active;
+            continue;
+        }
+        if trimmed == "#endif" {
+            if stack.pop().is_none() {
+                return Err(anyhow!("`#endif` without matching `#ifdef`/`#ifndef`"));
+            }
+            else_seen.pop();
+            continue;
+        }
+
+        if stack.iter().all(|&active| active) {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(anyhow!("unbalanced preprocessor directives: missing `#endif`"));
+    }
+
+    Ok(output)
+}